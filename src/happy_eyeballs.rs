@@ -0,0 +1,215 @@
+//! RFC 8305 "Happy Eyeballs" connection racing.
+//!
+//! Instead of firing every candidate address at once (wasteful) or trying them
+//! one at a time in sequence (slow when the first address is unreachable), the
+//! addresses are interleaved by family (IPv6 first) and dialed with a small
+//! stagger, using the first socket that completes a connection.
+
+use socket2::{Domain, Protocol, Socket, Type};
+use std::io;
+use std::net::{IpAddr, SocketAddr};
+use std::time::{Duration, Instant};
+
+use polling::{Event, Events, Poller};
+
+use crate::LocalBind;
+
+/// Sorts addresses into the RFC 8305 dialing order: alternating address
+/// families starting with IPv6, falling back to whichever family has
+/// addresses left once the other is exhausted.
+pub(crate) fn interleave_by_family(addrs: &[IpAddr]) -> Vec<IpAddr> {
+    let mut six = addrs.iter().filter(|a| a.is_ipv6()).copied();
+    let mut four = addrs.iter().filter(|a| a.is_ipv4()).copied();
+    let mut ordered = Vec::with_capacity(addrs.len());
+
+    loop {
+        match (six.next(), four.next()) {
+            (Some(a), Some(b)) => {
+                ordered.push(a);
+                ordered.push(b);
+            }
+            (Some(a), None) => {
+                ordered.push(a);
+                ordered.extend(six.by_ref());
+                break;
+            }
+            (None, Some(b)) => {
+                ordered.push(b);
+                ordered.extend(four.by_ref());
+                break;
+            }
+            (None, None) => break,
+        }
+    }
+
+    ordered
+}
+
+/// Races connections to `addrs:port` following RFC 8305, returning `true` as
+/// soon as one succeeds. `attempt_delay` is the stagger between launching
+/// successive connection attempts; `overall_timeout` bounds the whole race.
+pub(crate) fn race_connect(
+    addrs: &[IpAddr],
+    port: u16,
+    attempt_delay: Duration,
+    overall_timeout: Duration,
+    local_bind: &LocalBind,
+) -> bool {
+    let ordered = interleave_by_family(addrs);
+
+    if ordered.is_empty() {
+        return false;
+    }
+
+    let poller = match Poller::new() {
+        Ok(poller) => poller,
+        Err(_) => return false,
+    };
+
+    let deadline = Instant::now() + overall_timeout;
+    let mut candidates = ordered.into_iter().peekable();
+    let mut pending: Vec<(usize, Socket)> = Vec::new();
+    let mut next_key = 0usize;
+    let mut events = Events::new();
+
+    let mut last_launch = Instant::now();
+    launch_next(
+        &poller,
+        &mut candidates,
+        &mut pending,
+        &mut next_key,
+        port,
+        local_bind,
+    );
+
+    while !pending.is_empty() || candidates.peek().is_some() {
+        let now = Instant::now();
+
+        if now >= deadline {
+            break;
+        }
+
+        // Every candidate so far failed synchronously and none are pending
+        // yet: launch the next one right away instead of waiting out the
+        // rest of `attempt_delay`.
+        if pending.is_empty() {
+            last_launch = Instant::now();
+            launch_next(
+                &poller,
+                &mut candidates,
+                &mut pending,
+                &mut next_key,
+                port,
+                local_bind,
+            );
+            continue;
+        }
+
+        let until_deadline = deadline - now;
+        let until_next_launch = if candidates.peek().is_some() {
+            attempt_delay.saturating_sub(now.saturating_duration_since(last_launch))
+        } else {
+            until_deadline
+        };
+        let wait = until_deadline.min(until_next_launch);
+
+        events.clear();
+        let _ = poller.wait(&mut events, Some(wait));
+
+        let mut attempt_failed = false;
+
+        for event in events.iter() {
+            let Some(index) = pending.iter().position(|(key, _)| *key == event.key) else {
+                continue;
+            };
+
+            let (_, socket) = pending.remove(index);
+            let _ = poller.delete(&socket);
+
+            if matches!(socket.take_error(), Ok(None)) {
+                for (_, socket) in pending.drain(..) {
+                    let _ = poller.delete(&socket);
+                }
+
+                return true;
+            }
+
+            attempt_failed = true;
+        }
+
+        // A failed attempt should not have to wait out the rest of
+        // `attempt_delay` before the next address is tried.
+        let launch_due =
+            attempt_failed || Instant::now().duration_since(last_launch) >= attempt_delay;
+
+        if launch_due && candidates.peek().is_some() {
+            launch_next(
+                &poller,
+                &mut candidates,
+                &mut pending,
+                &mut next_key,
+                port,
+                local_bind,
+            );
+            last_launch = Instant::now();
+        }
+    }
+
+    false
+}
+
+/// Opens a non-blocking socket to the next candidate address that can be
+/// dialed, skipping over any that fail synchronously, and registers it with
+/// `poller` for writability. Returns `false` once `candidates` is exhausted
+/// without successfully launching one.
+fn launch_next(
+    poller: &Poller,
+    candidates: &mut std::iter::Peekable<impl Iterator<Item = IpAddr>>,
+    pending: &mut Vec<(usize, Socket)>,
+    next_key: &mut usize,
+    port: u16,
+    local_bind: &LocalBind,
+) -> bool {
+    while let Some(addr) = candidates.next() {
+        let domain = if addr.is_ipv6() { Domain::IPV6 } else { Domain::IPV4 };
+
+        let Ok(socket) = Socket::new(domain, Type::STREAM, Some(Protocol::TCP)) else {
+            continue;
+        };
+
+        if socket.set_nonblocking(true).is_err() {
+            continue;
+        }
+
+        if local_bind.apply(&socket, addr).is_err() {
+            continue;
+        }
+
+        let sockaddr: SocketAddr = SocketAddr::new(addr, port);
+
+        // A non-blocking connect almost always reports `WouldBlock`
+        // immediately; the actual result shows up later as a writability
+        // event. Any other error means this address failed synchronously,
+        // so move on to the next candidate right away.
+        match socket.connect(&sockaddr.into()) {
+            Ok(()) => {}
+            Err(e) if e.kind() == io::ErrorKind::WouldBlock => {}
+            Err(_) => continue,
+        }
+
+        let key = *next_key;
+        *next_key += 1;
+
+        // SAFETY: `socket` stays registered (and alive in `pending`) until it
+        // is either deleted from the poller or dropped together with its
+        // entry.
+        if unsafe { poller.add(&socket, Event::writable(key)) }.is_err() {
+            continue;
+        }
+
+        pending.push((key, socket));
+        return true;
+    }
+
+    false
+}
@@ -2,21 +2,77 @@ use ipnet::Ipv4Net;
 use ipnet::Ipv6Net;
 use rayon::iter::IntoParallelRefIterator;
 use rayon::iter::ParallelIterator;
+use socket2::{Domain, Protocol, Socket, Type};
 use std::fmt;
 use std::io;
 use std::net::IpAddr;
+use std::net::Ipv4Addr;
+use std::net::Ipv6Addr;
 use std::net::Shutdown;
 use std::net::SocketAddr;
 use std::net::TcpStream;
 use std::net::ToSocketAddrs;
+use std::net::UdpSocket;
 use std::str::FromStr;
+use std::sync::Arc;
 use std::time::Duration;
 
+mod happy_eyeballs;
+mod resolve;
+
+pub use resolve::CachingResolver;
+pub use resolve::GaiResolver;
+pub use resolve::Resolve;
+pub use resolve::ResolveHostError;
+
+/// Default stagger between successive RFC 8305 connection attempts.
+const DEFAULT_CONNECTION_ATTEMPT_DELAY: Duration = Duration::from_millis(250);
+
+/// The local source address and/or interface probes should originate from.
+#[derive(Debug, Clone, Default)]
+pub(crate) struct LocalBind {
+    address: Option<IpAddr>,
+    device: Option<String>,
+}
+
+impl LocalBind {
+    /// Binds `socket` (about to connect to an address of `family_hint`'s
+    /// family) to the configured local address and/or device, if any. The
+    /// local address is only bound if it is of the same family as
+    /// `family_hint`; otherwise the socket is left unbound.
+    fn apply(&self, socket: &Socket, family_hint: IpAddr) -> io::Result<()> {
+        if let Some(device) = &self.device {
+            bind_device(socket, device)?;
+        }
+
+        if let Some(address) = self.address {
+            if address.is_ipv6() == family_hint.is_ipv6() {
+                socket.bind(&SocketAddr::new(address, 0).into())?;
+            }
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(any(target_os = "android", target_os = "fuchsia", target_os = "linux"))]
+fn bind_device(socket: &Socket, device: &str) -> io::Result<()> {
+    socket.bind_device(Some(device.as_bytes()))
+}
+
+#[cfg(not(any(target_os = "android", target_os = "fuchsia", target_os = "linux")))]
+fn bind_device(_socket: &Socket, _device: &str) -> io::Result<()> {
+    Err(io::Error::new(
+        io::ErrorKind::Unsupported,
+        "binding to a network interface is not supported on this platform",
+    ))
+}
+
 #[derive(Debug)]
 pub struct HostParseError;
 
 #[derive(Debug)]
-struct ResolveHostError;
+pub struct PortParseError;
 
 /// Connects to a port and returns [true] if that was successful, [false] if not. The connection
 /// is subsequently closed. Wraps an [io::Error], but does not propagate timeout errors, as
@@ -67,26 +123,45 @@ impl fmt::Display for Host {
 }
 
 pub struct TcpPortCheck {
-    port: u16,
+    ports: Vec<u16>,
     protocol: IpProtocol,
     timeout: Duration,
     strategy: CheckStrategy,
+    connection_attempt_delay: Duration,
+    transport: Transport,
+    udp_probe: Option<Vec<u8>>,
+    udp_timeout_policy: UdpTimeoutPolicy,
+    udp_match: Option<Arc<dyn Fn(&[u8]) -> bool + Send + Sync>>,
+    local_bind: LocalBind,
 }
 
 impl TcpPortCheck {
     /// The default port check
     pub fn default() -> Self {
         TcpPortCheck {
-            port: 22,
+            ports: vec![22],
             protocol: IpProtocol::Both,
             timeout: Duration::new(1, 0),
             strategy: CheckStrategy::Any,
+            connection_attempt_delay: DEFAULT_CONNECTION_ATTEMPT_DELAY,
+            transport: Transport::Tcp,
+            udp_probe: None,
+            udp_timeout_policy: UdpTimeoutPolicy::AssumeOpen,
+            udp_match: None,
+            local_bind: LocalBind::default(),
         }
     }
 
-    /// Change the port
-    pub fn with_port(mut self, port: u16) -> Self {
-        self.port = port;
+    /// Change the port. A convenience wrapper around [Self::with_ports] for
+    /// the common single-port case.
+    pub fn with_port(self, port: u16) -> Self {
+        self.with_ports([port])
+    }
+
+    /// Change the set of ports to check. `is_online` and [Self::port_report]
+    /// evaluate every one of them.
+    pub fn with_ports(mut self, ports: impl IntoIterator<Item = u16>) -> Self {
+        self.ports = ports.into_iter().collect();
         self
     }
 
@@ -108,26 +183,123 @@ impl TcpPortCheck {
         self
     }
 
-    /// Returns true if the given host is online
+    /// Change the delay between successive connection attempts used by
+    /// [CheckStrategy::HappyEyeballs] (default 250ms, per RFC 8305).
+    pub fn with_connection_attempt_delay(mut self, delay: Duration) -> Self {
+        self.connection_attempt_delay = delay;
+        self
+    }
+
+    /// Change the transport used to probe ports (default [Transport::Tcp]).
+    pub fn with_transport(mut self, transport: Transport) -> Self {
+        self.transport = transport;
+        self
+    }
+
+    /// Set the payload sent with each UDP probe (default: an empty
+    /// datagram). Has no effect for [Transport::Tcp].
+    pub fn with_udp_probe(mut self, probe: impl Into<Vec<u8>>) -> Self {
+        self.udp_probe = Some(probe.into());
+        self
+    }
+
+    /// Change how a UDP probe that receives neither a response nor an
+    /// ICMP port-unreachable error before the timeout is interpreted
+    /// (default [UdpTimeoutPolicy::AssumeOpen], since most UDP services stay
+    /// silent unless they recognize the probe). Has no effect for
+    /// [Transport::Tcp].
+    pub fn with_udp_timeout_policy(mut self, policy: UdpTimeoutPolicy) -> Self {
+        self.udp_timeout_policy = policy;
+        self
+    }
+
+    /// Only count a UDP reply as confirming the port is open if `predicate`
+    /// returns true for its payload, instead of accepting any datagram
+    /// (default: any datagram counts). Has no effect for [Transport::Tcp].
+    pub fn with_udp_match(mut self, predicate: impl Fn(&[u8]) -> bool + Send + Sync + 'static) -> Self {
+        self.udp_match = Some(Arc::new(predicate));
+        self
+    }
+
+    /// Make probes originate from `address` instead of whatever the OS
+    /// routing table picks. Useful on multi-homed hosts or VPN setups where
+    /// reachability must be tested from a particular local network path.
+    /// Probes to addresses of the other IP family are not bound to `address`.
+    pub fn with_local_address(mut self, address: IpAddr) -> Self {
+        self.local_bind.address = Some(address);
+        self
+    }
+
+    /// Bind probes to a specific network interface (e.g. `"eth1"`), via
+    /// `SO_BINDTODEVICE`. Only supported on Linux, Android, and Fuchsia.
+    pub fn with_bound_device(mut self, device: impl Into<String>) -> Self {
+        self.local_bind.device = Some(device.into());
+        self
+    }
+
+    /// Returns true if the given host is online: with [CheckStrategy::Any], at
+    /// least one port is open on at least one address; with
+    /// [CheckStrategy::All], every requested port is open on every matching
+    /// address.
     pub fn is_online(&self, host: &Host) -> bool {
-        let addrs: Vec<&IpAddr> = match self.protocol {
-            IpProtocol::Both => host.addresses.iter().collect(),
-            IpProtocol::V4 => host.addresses.iter().filter(|a| a.is_ipv4()).collect(),
-            IpProtocol::V6 => host.addresses.iter().filter(|a| a.is_ipv6()).collect(),
-        };
+        let addrs = self.addresses(host);
 
-        if addrs.is_empty() {
+        if addrs.is_empty() || self.ports.is_empty() {
             return false;
         }
 
-        let iter = addrs.par_iter();
-
-        match self.strategy {
-            CheckStrategy::Any => iter.any(|addr| self.is_open_port(addr)),
-            CheckStrategy::All => iter.all(|addr| self.is_open_port(addr)),
+        match (&self.strategy, self.transport) {
+            (CheckStrategy::HappyEyeballs, Transport::Tcp) => {
+                let addrs: Vec<IpAddr> = addrs.into_iter().copied().collect();
+                self.ports.par_iter().any(|&port| {
+                    happy_eyeballs::race_connect(
+                        &addrs,
+                        port,
+                        self.connection_attempt_delay,
+                        self.timeout,
+                        &self.local_bind,
+                    )
+                })
+            }
+            (CheckStrategy::Any, _) | (CheckStrategy::HappyEyeballs, Transport::Udp) => self
+                .ports
+                .par_iter()
+                .any(|&port| addrs.par_iter().any(|addr| self.is_open_port(addr, port))),
+            (CheckStrategy::All, _) => self
+                .ports
+                .par_iter()
+                .all(|&port| addrs.par_iter().all(|addr| self.is_open_port(addr, port))),
         }
     }
 
+    /// Checks every requested port individually, returning `(port, online)`
+    /// pairs. A port is considered online if it is open on any address that
+    /// matches [Self::with_protocol].
+    pub fn port_report(&self, host: &Host) -> Vec<(u16, bool)> {
+        let addrs = self.addresses(host);
+
+        self.ports
+            .iter()
+            .map(|&port| {
+                let online = match (&self.strategy, self.transport) {
+                    (CheckStrategy::HappyEyeballs, Transport::Tcp) => {
+                        let addrs: Vec<IpAddr> = addrs.iter().map(|a| **a).collect();
+                        happy_eyeballs::race_connect(
+                            &addrs,
+                            port,
+                            self.connection_attempt_delay,
+                            self.timeout,
+                            &self.local_bind,
+                        )
+                    }
+                    _ => addrs.par_iter().any(|addr| self.is_open_port(addr, port)),
+                };
+
+                (port, online)
+            })
+            .collect()
+    }
+
     /// Creates a new vector from the given references, containing only online hosts.
     pub fn collect_online(&self, hosts: &[Host]) -> Vec<Host> {
         hosts
@@ -137,16 +309,82 @@ impl TcpPortCheck {
             .collect()
     }
 
-    /// Check if we can create a connection to the given socket
-    fn is_open_port(&self, addr: &IpAddr) -> bool {
-        let socket = SocketAddr::new(*addr, self.port);
+    /// The addresses of `host` that match the configured protocol.
+    fn addresses<'a>(&self, host: &'a Host) -> Vec<&'a IpAddr> {
+        match self.protocol {
+            IpProtocol::Both => host.addresses.iter().collect(),
+            IpProtocol::V4 => host.addresses.iter().filter(|a| a.is_ipv4()).collect(),
+            IpProtocol::V6 => host.addresses.iter().filter(|a| a.is_ipv6()).collect(),
+        }
+    }
+
+    /// Check if the given socket answers on the configured transport
+    fn is_open_port(&self, addr: &IpAddr, port: u16) -> bool {
+        match self.transport {
+            Transport::Tcp => self.is_open_tcp_port(addr, port),
+            Transport::Udp => self.is_open_udp_port(addr, port),
+        }
+    }
+
+    /// Check if we can create a TCP connection to the given socket
+    fn is_open_tcp_port(&self, addr: &IpAddr, port: u16) -> bool {
+        let domain = if addr.is_ipv6() { Domain::IPV6 } else { Domain::IPV4 };
+
+        let Ok(socket) = Socket::new(domain, Type::STREAM, Some(Protocol::TCP)) else {
+            return false;
+        };
 
-        if let Ok(stream) = TcpStream::connect_timeout(&socket, self.timeout) {
-            let _ = stream.shutdown(Shutdown::Both);
-            return true;
+        if self.local_bind.apply(&socket, *addr).is_err() {
+            return false;
+        }
+
+        let remote = SocketAddr::new(*addr, port);
+
+        if socket.connect_timeout(&remote.into(), self.timeout).is_err() {
+            return false;
         }
 
-        false
+        let stream: TcpStream = socket.into();
+        let _ = stream.shutdown(Shutdown::Both);
+
+        true
+    }
+
+    /// Probe a UDP socket: send a datagram and see whether we get a reply or
+    /// an ICMP port-unreachable error back before the timeout. A reply only
+    /// counts if it passes [Self::with_udp_match] (when set). A silent
+    /// timeout is resolved using [Self::with_udp_timeout_policy].
+    fn is_open_udp_port(&self, addr: &IpAddr, port: u16) -> bool {
+        let remote = SocketAddr::new(*addr, port);
+        let local: SocketAddr = match remote {
+            SocketAddr::V4(_) => (Ipv4Addr::UNSPECIFIED, 0).into(),
+            SocketAddr::V6(_) => (Ipv6Addr::UNSPECIFIED, 0).into(),
+        };
+
+        let socket = match UdpSocket::bind(local) {
+            Ok(socket) => socket,
+            Err(_) => return false,
+        };
+
+        if socket.set_read_timeout(Some(self.timeout)).is_err() || socket.connect(remote).is_err()
+        {
+            return false;
+        }
+
+        let probe: &[u8] = self.udp_probe.as_deref().unwrap_or(&[]);
+        if socket.send(probe).is_err() {
+            return false;
+        }
+
+        let mut buf = [0u8; 512];
+        match socket.recv(&mut buf) {
+            Ok(len) => match &self.udp_match {
+                Some(predicate) => predicate(&buf[..len]),
+                None => true,
+            },
+            Err(e) if e.kind() == io::ErrorKind::ConnectionRefused => false,
+            Err(_) => self.udp_timeout_policy == UdpTimeoutPolicy::AssumeOpen,
+        }
     }
 }
 
@@ -171,43 +409,71 @@ pub enum CheckStrategy {
 
     /// Consider a host online if all known addresses are online
     All,
+
+    /// Race all known addresses following RFC 8305 ("Happy Eyeballs"):
+    /// addresses are interleaved by family (IPv6 first) and dialed with a
+    /// short stagger, and the host is online as soon as one connects.
+    HappyEyeballs,
 }
 
-impl FromStr for Host {
-    type Err = HostParseError;
+/// Defines the wire protocol used to probe a port
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Transport {
+    /// Probe by establishing a TCP connection
+    Tcp,
 
-    fn from_str(host: &str) -> Result<Self, Self::Err> {
-        // If the host is an address, parse it
-        if let Ok(address) = IpAddr::from_str(host) {
-            return Ok(Host {
-                name: String::from(host),
-                addresses: vec![address],
-            });
-        };
+    /// Probe by sending a UDP datagram and watching for a reply or an ICMP
+    /// port-unreachable error
+    Udp,
+}
+
+/// Defines how a UDP probe that times out without a reply or a
+/// port-unreachable error is interpreted, since UDP has no handshake to
+/// confirm a port is listening.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum UdpTimeoutPolicy {
+    /// Assume the port is open
+    AssumeOpen,
 
-        // If not an address, resolve it
-        if let Ok(addresses) = resolve_hostname(host) {
-            return Ok(Host {
-                name: String::from(host),
+    /// Assume the port is closed
+    AssumeClosed,
+}
+
+impl Host {
+    /// Resolves `name` into a [Host] using the given [Resolve]r. If `name` is
+    /// already an IP address, it is wrapped directly without a lookup.
+    pub fn resolve_with<R: Resolve>(name: &str, resolver: &R) -> Result<Self, HostParseError> {
+        match resolve::resolve_with(name, resolver) {
+            Ok(addresses) => Ok(Host {
+                name: String::from(name),
                 addresses,
-            });
-        };
+            }),
+            Err(_) => Err(HostParseError),
+        }
+    }
+}
 
-        Err(HostParseError)
+impl FromStr for Host {
+    type Err = HostParseError;
+
+    fn from_str(host: &str) -> Result<Self, Self::Err> {
+        Host::resolve_with(host, &GaiResolver)
     }
 }
 
-/// Takes a list of host-names and yields all hosts that can be resolved
+/// Takes a list of host-names and yields all hosts that can be resolved,
+/// using the default system resolver. See [resolve_hosts_with] to supply a
+/// custom [Resolve]r instead.
 pub fn resolve_hosts(hosts: &[String]) -> Vec<Host> {
+    resolve_hosts_with(hosts, &GaiResolver)
+}
+
+/// Takes a list of host-names and yields all hosts that can be resolved,
+/// using `resolver` to look up names that aren't already IP addresses.
+pub fn resolve_hosts_with<R: Resolve + Sync>(hosts: &[String], resolver: &R) -> Vec<Host> {
     hosts
         .par_iter()
-        .filter_map(|host| {
-            if let Ok(host) = Host::from_str(host) {
-                Some(host)
-            } else {
-                None
-            }
-        })
+        .filter_map(|host| Host::resolve_with(host, resolver).ok())
         .collect()
 }
 
@@ -247,9 +513,42 @@ pub fn expand_subnets(hosts: &[String]) -> Vec<String> {
     expanded
 }
 
+/// Parses a port specification into the individual ports it describes.
+/// Accepts a single port (`80`), a range (`8000-10000`), or a comma-separated
+/// combination of both (`20-25,80,443`).
+pub fn parse_ports(spec: &str) -> Result<Vec<u16>, PortParseError> {
+    let mut ports = Vec::new();
+
+    for part in spec.split(',') {
+        let part = part.trim();
+
+        if let Some((start, end)) = part.split_once('-') {
+            let start: u16 = start.trim().parse().map_err(|_| PortParseError)?;
+            let end: u16 = end.trim().parse().map_err(|_| PortParseError)?;
+
+            if start > end {
+                return Err(PortParseError);
+            }
+
+            ports.extend(start..=end);
+        } else {
+            ports.push(part.parse().map_err(|_| PortParseError)?);
+        }
+    }
+
+    if ports.is_empty() {
+        return Err(PortParseError);
+    }
+
+    Ok(ports)
+}
+
 /// Resolve the given hostname and return a vector of IP addresses. If given
 /// an IP address, it will be wrapped in a vector sans lookup.
-fn resolve_hostname(name: &str) -> Result<Vec<IpAddr>, ResolveHostError> {
+///
+/// This is the lookup used by [GaiResolver]; most callers should go through
+/// [Resolve] instead of calling it directly.
+pub(crate) fn resolve_hostname(name: &str) -> Result<Vec<IpAddr>, ResolveHostError> {
     // If the hostname is an IP address, return it
     if let Ok(address) = IpAddr::from_str(name) {
         return Ok(vec![address]);
@@ -272,6 +571,49 @@ mod tests {
     use std::net::Ipv4Addr;
     use std::net::Ipv6Addr;
 
+    struct StubResolver(Vec<IpAddr>);
+
+    impl Resolve for StubResolver {
+        fn resolve(&self, _name: &str) -> Result<Vec<IpAddr>, ResolveHostError> {
+            Ok(self.0.clone())
+        }
+    }
+
+    #[test]
+    fn test_resolve_with_custom_resolver() {
+        let addresses = vec![IpAddr::V4(Ipv4Addr::new(203, 0, 113, 1))];
+        let resolver = StubResolver(addresses.clone());
+
+        let host = Host::resolve_with("example.test", &resolver).unwrap();
+
+        assert_eq!(&host.name, "example.test");
+        assert_eq!(host.addresses, addresses);
+    }
+
+    /// Answers with a different address on every lookup, so that equal
+    /// answers can only come from a cache.
+    struct CountingResolver(std::cell::Cell<u8>);
+
+    impl Resolve for CountingResolver {
+        fn resolve(&self, _name: &str) -> Result<Vec<IpAddr>, ResolveHostError> {
+            self.0.set(self.0.get() + 1);
+            Ok(vec![IpAddr::V4(Ipv4Addr::new(203, 0, 113, self.0.get()))])
+        }
+    }
+
+    #[test]
+    fn test_caching_resolver_caches_within_ttl() {
+        let resolver = CachingResolver::new(
+            CountingResolver(std::cell::Cell::new(1)),
+            Duration::from_secs(60),
+        );
+
+        let first = resolver.resolve("example.test").unwrap();
+        let second = resolver.resolve("example.test").unwrap();
+
+        assert_eq!(first, second);
+    }
+
     #[test]
     fn test_resolve_hostname() {
         let address = resolve_hostname("localhost").unwrap();
@@ -320,6 +662,21 @@ mod tests {
         );
     }
 
+    #[test]
+    fn test_parse_ports() {
+        assert_eq!(parse_ports("80").unwrap(), vec![80]);
+        assert_eq!(parse_ports("22,80,443").unwrap(), vec![22, 80, 443]);
+        assert_eq!(parse_ports("20-22").unwrap(), vec![20, 21, 22]);
+        assert_eq!(
+            parse_ports("20-22,80,443").unwrap(),
+            vec![20, 21, 22, 80, 443]
+        );
+
+        assert!(parse_ports("").is_err());
+        assert!(parse_ports("not-a-port").is_err());
+        assert!(parse_ports("100-50").is_err());
+    }
+
     #[test]
     fn is_tcp_port_online() {
         let host = Host::from_str("google.com").unwrap();
@@ -350,4 +707,82 @@ mod tests {
             .with_protocol(IpProtocol::V4)
             .is_online(&host));
     }
+
+    #[test]
+    fn happy_eyeballs_falls_back_past_a_fast_refusal() {
+        // ::1 refuses immediately (nothing listens there), so the race must
+        // move on to the reachable IPv4 address instead of giving up.
+        let host = Host {
+            name: String::from("fast-refusal.test"),
+            addresses: vec![
+                IpAddr::V6(Ipv6Addr::LOCALHOST),
+                IpAddr::V4(Ipv4Addr::new(1, 1, 1, 1)),
+            ],
+        };
+
+        assert!(TcpPortCheck::default()
+            .with_port(80)
+            .with_strategy(CheckStrategy::HappyEyeballs)
+            .is_online(&host));
+    }
+
+    #[test]
+    fn is_tcp_port_online_with_local_address() {
+        let host = Host::from_str("google.com").unwrap();
+
+        // Binding to the unspecified address is a no-op, and should behave
+        // exactly like not binding at all
+        assert!(TcpPortCheck::default()
+            .with_port(80)
+            .with_protocol(IpProtocol::V4)
+            .with_local_address(IpAddr::V4(Ipv4Addr::UNSPECIFIED))
+            .is_online(&host));
+    }
+
+    #[test]
+    fn is_udp_port_online() {
+        let host = Host::from_str("8.8.8.8").unwrap();
+
+        // DNS answers a well-formed query
+        assert!(TcpPortCheck::default()
+            .with_port(53)
+            .with_transport(Transport::Udp)
+            .with_udp_probe(vec![
+                0, 0, 1, 0, 0, 1, 0, 0, 0, 0, 0, 0, 0, 0, 1, 0, 1,
+            ])
+            .is_online(&host));
+
+        // A closed UDP port with AssumeClosed on timeout is reported offline
+        assert!(!TcpPortCheck::default()
+            .with_port(1)
+            .with_transport(Transport::Udp)
+            .with_udp_timeout_policy(UdpTimeoutPolicy::AssumeClosed)
+            .with_timeout(Duration::from_millis(200))
+            .is_online(&host));
+    }
+
+    #[test]
+    fn is_udp_port_online_with_match_predicate() {
+        let host = Host::from_str("8.8.8.8").unwrap();
+        let query = vec![
+            0, 0, 1, 0, 0, 1, 0, 0, 0, 0, 0, 0, 0, 0, 1, 0, 1,
+        ];
+
+        // A DNS reply always starts with the same 2-byte transaction ID we sent
+        assert!(TcpPortCheck::default()
+            .with_port(53)
+            .with_transport(Transport::Udp)
+            .with_udp_probe(query.clone())
+            .with_udp_match(|reply| reply.starts_with(&[0, 0]))
+            .is_online(&host));
+
+        // A predicate that can never match turns an otherwise-valid reply into
+        // an offline result
+        assert!(!TcpPortCheck::default()
+            .with_port(53)
+            .with_transport(Transport::Udp)
+            .with_udp_probe(query)
+            .with_udp_match(|_| false)
+            .is_online(&host));
+    }
 }
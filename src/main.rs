@@ -1,11 +1,16 @@
 use clap::Parser;
 use is_online::expand_subnets;
-use is_online::resolve_hosts;
+use is_online::parse_ports;
+use is_online::resolve_hosts_with;
+use is_online::CachingResolver;
 use is_online::CheckStrategy;
+use is_online::GaiResolver;
 use is_online::IpProtocol;
 use is_online::TcpPortCheck;
+use is_online::Transport;
 use std::cmp::min;
 use std::collections::HashSet;
+use std::net::IpAddr;
 use std::process;
 use std::thread::available_parallelism;
 use std::thread::sleep;
@@ -20,14 +25,28 @@ struct Cli {
     #[clap()]
     hosts: Vec<String>,
 
-    /// Port to check
+    /// Port(s) to check. Accepts a single port, a range (`8000-10000`), or a
+    /// comma-separated combination of both (`20-25,80,443`).
     #[clap(short, long, default_value = "22")]
-    port: u16,
+    port: String,
 
-    /// TCP connection timeout in milliseconds
+    /// Connection timeout in milliseconds
     #[clap(short, long, default_value = "1000")]
     timeout: u32,
 
+    /// Probe via UDP instead of TCP
+    #[clap(long)]
+    udp: bool,
+
+    /// Source address probes should originate from, instead of whatever the
+    /// OS routing table picks
+    #[clap(long = "source")]
+    source: Option<IpAddr>,
+
+    /// Network interface probes should be bound to (Linux only)
+    #[clap(long = "interface")]
+    interface: Option<String>,
+
     /// Limit to IPv4
     #[clap(short = '4')]
     ipv4_only: bool,
@@ -97,6 +116,23 @@ impl Cli {
         }
     }
 
+    /// The requested ports, parsed from the `--port` argument
+    fn ports(&self) -> Vec<u16> {
+        parse_ports(&self.port).unwrap_or_else(|_| {
+            eprintln!("error: invalid port specification: {}", self.port);
+            process::exit(2);
+        })
+    }
+
+    /// The transport to probe with
+    fn transport(&self) -> Transport {
+        if self.udp {
+            Transport::Udp
+        } else {
+            Transport::Tcp
+        }
+    }
+
     /// The timeout as duration
     fn timeout_duration(&self) -> Duration {
         Duration::from_millis(self.timeout as u64)
@@ -104,11 +140,22 @@ impl Cli {
 
     /// The port check to execute
     fn tcp_port_check(&self) -> TcpPortCheck {
-        TcpPortCheck::default()
-            .with_port(self.port)
+        let mut check = TcpPortCheck::default()
+            .with_ports(self.ports())
             .with_protocol(self.protocol())
             .with_timeout(self.timeout_duration())
             .with_strategy(self.strategy())
+            .with_transport(self.transport());
+
+        if let Some(source) = self.source {
+            check = check.with_local_address(source);
+        }
+
+        if let Some(interface) = &self.interface {
+            check = check.with_bound_device(interface.clone());
+        }
+
+        check
     }
 
     /// Clear the screen
@@ -153,6 +200,10 @@ fn main() {
     // Build the port check
     let check = cli.tcp_port_check();
 
+    // Cache DNS lookups across --wait iterations instead of re-resolving
+    // every host on every pass
+    let resolver = CachingResolver::new(GaiResolver, Duration::from_secs(60));
+
     // Read the hosts from the list, or from stdin
     let hosts = match cli.hosts.is_empty() {
         true => expand_subnets(
@@ -172,12 +223,12 @@ fn main() {
         }
 
         // Gather the resolved/online hosts
-        let resolved = resolve_hosts(&hosts);
-        let online = check.collect_online(&resolved);
+        let resolved_hosts = resolve_hosts_with(&hosts, &resolver);
+        let online_hosts = check.collect_online(&resolved_hosts);
 
         // Keep a set of resolved/online hosts
-        let resolved: HashSet<String> = resolved.iter().map(|h| h.name.to_string()).collect();
-        let online: HashSet<String> = online.iter().map(|h| h.name.to_string()).collect();
+        let resolved: HashSet<String> = resolved_hosts.iter().map(|h| h.name.to_string()).collect();
+        let online: HashSet<String> = online_hosts.iter().map(|h| h.name.to_string()).collect();
 
         // Exit with a 1 --fail is given and not all hosts are online
         let exit_code = if cli.fail && online.len() != hosts.len() {
@@ -186,15 +237,22 @@ fn main() {
             0
         };
 
-        // Note what went wrong
+        // Note what went wrong, and the status of every requested port
         if !cli.quiet {
             for name in hosts.iter().by_ref() {
                 if !resolved.contains(name) {
                     println!("{name} could not be resolved");
-                } else if !online.contains(name) {
-                    println!("{name}:{} is {}", cli.port, cli.format_failure("offline"));
-                } else {
-                    println!("{name}:{} is {}", cli.port, cli.format_success("online"));
+                    continue;
+                }
+
+                let host = resolved_hosts.iter().find(|h| &h.name == name).unwrap();
+
+                for (port, port_online) in check.port_report(host) {
+                    if port_online {
+                        println!("{name}:{port} is {}", cli.format_success("online"));
+                    } else {
+                        println!("{name}:{port} is {}", cli.format_failure("offline"));
+                    }
                 }
             }
         }
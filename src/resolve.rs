@@ -0,0 +1,84 @@
+//! Pluggable DNS resolution, modeled on hyper's `Resolve`/`GaiResolver` split:
+//! the default resolver calls the blocking libc resolver, but callers
+//! can supply their own (a DoH client, a test double, a cache) anywhere a
+//! [Resolve] is accepted.
+
+use std::collections::HashMap;
+use std::net::IpAddr;
+use std::str::FromStr;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+#[derive(Debug)]
+pub struct ResolveHostError;
+
+/// Resolves a host name to the IP addresses it answers to.
+pub trait Resolve {
+    /// Looks up `name`, returning every address it resolves to.
+    fn resolve(&self, name: &str) -> Result<Vec<IpAddr>, ResolveHostError>;
+}
+
+/// The default resolver: looks names up via the system's blocking
+/// getaddrinfo-based resolver.
+#[derive(Debug, Default, Clone, Copy)]
+pub struct GaiResolver;
+
+impl Resolve for GaiResolver {
+    fn resolve(&self, name: &str) -> Result<Vec<IpAddr>, ResolveHostError> {
+        crate::resolve_hostname(name)
+    }
+}
+
+/// Wraps a [Resolve] and memoizes its answers for `ttl`, so that repeated
+/// lookups of the same name (e.g. successive `--wait` iterations) don't
+/// re-hit the system resolver on every pass.
+pub struct CachingResolver<R = GaiResolver> {
+    inner: R,
+    ttl: Duration,
+    cache: Mutex<HashMap<String, (Vec<IpAddr>, Instant)>>,
+}
+
+impl<R: Resolve> CachingResolver<R> {
+    /// Wraps `inner`, caching each resolved name for `ttl`.
+    pub fn new(inner: R, ttl: Duration) -> Self {
+        CachingResolver {
+            inner,
+            ttl,
+            cache: Mutex::new(HashMap::new()),
+        }
+    }
+}
+
+impl<R: Resolve> Resolve for CachingResolver<R> {
+    fn resolve(&self, name: &str) -> Result<Vec<IpAddr>, ResolveHostError> {
+        let now = Instant::now();
+
+        if let Some((addresses, expires_at)) = self.cache.lock().unwrap().get(name) {
+            if *expires_at > now {
+                return Ok(addresses.clone());
+            }
+        }
+
+        let addresses = self.inner.resolve(name)?;
+
+        self.cache
+            .lock()
+            .unwrap()
+            .insert(name.to_string(), (addresses.clone(), now + self.ttl));
+
+        Ok(addresses)
+    }
+}
+
+/// Resolves `name` with `resolver`, or wraps it directly if it is already an
+/// IP address.
+pub(crate) fn resolve_with<R: Resolve>(
+    name: &str,
+    resolver: &R,
+) -> Result<Vec<IpAddr>, ResolveHostError> {
+    if let Ok(address) = IpAddr::from_str(name) {
+        return Ok(vec![address]);
+    }
+
+    resolver.resolve(name)
+}